@@ -2,19 +2,130 @@ pub mod parser;
 pub mod log;
 pub mod tests;
 
-use std::{process::Command, collections::HashMap, error::Error, fs};
+use std::{process::Command, collections::HashMap, error::Error, fs, path::Path, str::FromStr, time::Duration};
 use parser::CliArgs;
 use log::Logger;
-use zbus::{dbus_interface, SignalContext};
+use chrono::{DateTime, NaiveDateTime};
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use zbus::{dbus_interface, zvariant::Value, Connection, SignalContext};
 
+/// Default object path this manager is served at. `from_args` starts
+/// `object_path` out with it; `set_connection` replaces it with the path
+/// the caller hands in.
+const OBJECT_PATH: &str = "/org/regolith/Config";
+
+/// Default state file `init()` restores from when started without a config
+/// file to load. Nothing writes it automatically: it holds whatever a client
+/// last saved there with `dump`.
+const DEFAULT_STATE_PATH: &str = "/var/lib/resmand/state.json";
+
+/// A conversion to apply to a resource's raw string value. Names accepted by
+/// `FromStr`: `"int"`/`"integer"`, `"float"`, `"bool"`/`"boolean"`,
+/// `"string"`/`"bytes"`, `"timestamp"` (RFC3339) and `"timestamp|<fmt>"`
+/// (strftime-style format string).
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Conversion {
+    Bytes,
+    Integer,
+    Float,
+    Boolean,
+    Timestamp,
+    TimestampFmt(String),
+}
+
+impl FromStr for Conversion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "int" | "integer" => Ok(Conversion::Integer),
+            "float" => Ok(Conversion::Float),
+            "bool" | "boolean" => Ok(Conversion::Boolean),
+            "string" | "bytes" => Ok(Conversion::Bytes),
+            "timestamp" => Ok(Conversion::Timestamp),
+            _ => match s.split_once('|') {
+                Some(("timestamp", fmt)) => Ok(Conversion::TimestampFmt(fmt.to_string())),
+                _ => Err(format!("unknown conversion '{s}'")),
+            },
+        }
+    }
+}
+
+/// A layered resource source: a file loaded at a given priority, plus the
+/// key/value pairs it parsed to. Sources are kept sorted by priority
+/// (lowest number first) so the first source holding a key is the one that
+/// wins when resolving it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Source {
+    path: String,
+    priority: i32,
+    values: HashMap<String, String>,
+}
+
+/// BLAKE3 digest over the raw file bytes plus the preprocessor command, so a
+/// cache hit requires both the file contents and how it would be
+/// preprocessed to match what produced the cached output.
+fn preprocess_cache_key(preprocessor: &str, file_bytes: &[u8]) -> String {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(file_bytes);
+    hasher.update(preprocessor.as_bytes());
+    hasher.finalize().to_string()
+}
+
+/// Serialize `resources` to `path` atomically: write to `path.tmp`, move any
+/// existing file at `path` to `path.bak`, then rename the temp file into
+/// place. This mirrors the write-temp-then-rename pattern used elsewhere so
+/// a crash or power loss can't leave a half-written file.
+fn write_resources_atomic(resources: &HashMap<String, String>, path: &str) -> Result<(), Box<dyn Error>> {
+    let json = serde_json::to_string_pretty(resources)?;
+    let tmp_path = format!("{path}.tmp");
+    fs::write(&tmp_path, json)?;
+    if Path::new(path).exists() {
+        fs::rename(path, format!("{path}.bak"))?;
+    }
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Read a resources map previously written by `write_resources_atomic` from `path`.
+fn read_resources(path: &str) -> Result<HashMap<String, String>, Box<dyn Error>> {
+    let json = fs::read_to_string(path)?;
+    Ok(serde_json::from_str(&json)?)
+}
+
+/// Resolve the effective key/value view: each source's values layered in
+/// *reverse* priority order (so the lowest-priority-number source is applied
+/// last and wins among sources), with `resources` (directly
+/// `set_resource`/`add_resource`/`load`/`merge`d values) layered on top of
+/// all of them. A runtime-set value always wins over a file source, so
+/// `set_resource` is observable even for a key a source also defines.
+fn resolve_effective(resources: &HashMap<String, String>, sources: &[Source]) -> HashMap<String, String> {
+    let mut effective = HashMap::new();
+    for source in sources.iter().rev() {
+        for (k, v) in &source.values {
+            effective.insert(k.clone(), v.clone());
+        }
+    }
+    effective.extend(resources.clone());
+    effective
+}
 
 /// Stores and manages the resources
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug)]
 pub struct ResourceManager {
     resources: HashMap<String, String>,
     preprocessor: String,
     logger: Logger,
     args: CliArgs,
+    watch_handles: HashMap<String, tokio::task::JoinHandle<()>>,
+    connection: Option<Connection>,
+    /// Object path this manager is actually served at on `connection`, so
+    /// the watcher task can look itself back up on the object server
+    /// instead of assuming a hardcoded path.
+    object_path: String,
+    preprocess_cache: HashMap<String, String>,
+    sources: Vec<Source>,
 }
 
 
@@ -33,20 +144,198 @@ impl ResourceManager {
             preprocessor,
             logger,
             args: args.clone(),
+            watch_handles: HashMap::new(),
+            connection: None,
+            object_path: OBJECT_PATH.to_string(),
+            preprocess_cache: HashMap::new(),
+            sources: Vec::new(),
         }
     }
 
-    /// Initialize ResourceManager fields based on the values in args
-    pub fn init(&mut self) {
+    /// Record the DBus connection this manager is served on and the object
+    /// path it's served at, so background tasks (e.g. the file watcher) can
+    /// reach back into the object server at the right path.
+    pub fn set_connection(&mut self, connection: Connection, object_path: impl Into<String>) {
+        self.connection = Some(connection);
+        self.object_path = object_path.into();
+    }
+
+    /// Initialize ResourceManager fields based on the values in args.
+    ///
+    /// Takes the DBus `connection` this manager is served on (and the
+    /// `object_path` it's served at) and records it via `set_connection`
+    /// before doing anything else, so the startup auto-watch this method
+    /// arms always has a connection to reach back into the object server
+    /// with - it can't silently degrade into a no-op watch.
+    pub fn init(&mut self, connection: Connection, object_path: impl Into<String>) {
+        self.set_connection(connection, object_path);
         self.logger.info("Initializing Daemon...");
         let filename = match &self.args.load {
             Some(file) => file,
             None => match &self.args.filename {
                 Some(x) => x,
-                None => return
+                None => {
+                    match self.restore_from_file(DEFAULT_STATE_PATH) {
+                        Ok(()) => self.logger.info(&format!("restored saved state from {DEFAULT_STATE_PATH}")),
+                        Err(e) => self.logger.info(&format!("no saved state restored: {e}")),
+                    }
+                    return;
+                }
             }
         };
-        self.load_from_file(&filename.clone());
+        let filename = filename.clone();
+        self.load_from_file(&filename);
+        self.start_watch(filename);
+    }
+
+    /// Dump `resources` to `path`; see `write_resources_atomic`.
+    fn dump_to_file(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        write_resources_atomic(&self.resources, path)
+    }
+
+    /// Read a resources map previously written by `dump_to_file` from `path`
+    /// and merge it into `resources`, overwriting existing keys.
+    fn restore_from_file(&mut self, path: &str) -> Result<(), Box<dyn Error>> {
+        for (k, v) in read_resources(path)? {
+            self.resources.insert(k, v);
+        }
+        Ok(())
+    }
+
+    /// Read and parse `path` into a `Source` at `priority`, replacing any
+    /// existing source for the same path, then re-sort `sources` so the
+    /// highest-priority (lowest number) source is checked first.
+    fn load_source(&mut self, path: &str, priority: i32) -> Result<(), Box<dyn Error>> {
+        let config_str = self.get_preprocessed_file(path)?;
+        let values = self.parse_config(&config_str);
+        self.sources.retain(|s| s.path != path);
+        self.sources.push(Source {
+            path: path.to_string(),
+            priority,
+            values,
+        });
+        self.sources.sort_by_key(|s| s.priority);
+        Ok(())
+    }
+
+    /// Build the effective key/value view; see `resolve_effective`.
+    fn effective_resources(&self) -> HashMap<String, String> {
+        resolve_effective(&self.resources, &self.sources)
+    }
+
+    /// Spawn a background task that watches `path` and re-applies it
+    /// whenever the file changes on disk, then notifies DBus clients via
+    /// `resources_changed`. Rapid-fire events for the same path (editors
+    /// that write-then-rename) are debounced.
+    ///
+    /// A reload always merges (overwrites), even though a plain `load()`
+    /// doesn't: the whole point of watching is to pick up edits to keys the
+    /// file already defined, and `load_from_file`'s entry()-based semantics
+    /// would otherwise treat the file's own prior values as already-set and
+    /// never apply the new ones.
+    ///
+    /// Note: a reload always re-preprocesses with the manager's *current*
+    /// global `preprocessor`/`nocpp` settings, not whatever `cpp`/`nocpp`
+    /// the file was originally loaded with - per-file preprocessing
+    /// overrides aren't tracked (there's no per-watch record of them) and
+    /// are intentionally unsupported for now.
+    fn start_watch(&mut self, path: String) {
+        let connection = match &self.connection {
+            Some(conn) => conn.clone(),
+            None => {
+                self.logger.warn("cannot watch - no DBus connection registered yet");
+                return;
+            }
+        };
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        let mut watcher = match notify::recommended_watcher(move |res| {
+            let _ = tx.send(res);
+        }) {
+            Ok(w) => w,
+            Err(e) => {
+                self.logger.warn(&format!("failed to create watcher for {path}: {e}"));
+                return;
+            }
+        };
+
+        // Watch the parent directory rather than the file itself: an editor's
+        // write-then-rename replaces the inode, which would leave a
+        // file-level inotify watch pointing at the unlinked old inode and
+        // silently deaf to all further edits. The `watch_name` filter below
+        // still ignores unrelated siblings in that directory.
+        let parent = Path::new(&path).parent().unwrap_or_else(|| Path::new("."));
+        if let Err(e) = watcher.watch(parent, RecursiveMode::NonRecursive) {
+            self.logger.warn(&format!("failed to watch {path}: {e}"));
+            return;
+        }
+
+        let watch_path = path.clone();
+        let watch_name = Path::new(&watch_path).file_name().map(|n| n.to_owned());
+        let object_path = self.object_path.clone();
+        let logger = self.logger.clone();
+        let handle = tokio::spawn(async move {
+            // Held for the lifetime of this task to keep the watch alive.
+            let _watcher: RecommendedWatcher = watcher;
+            // Trailing-edge debounce: a qualifying event arms `pending` and
+            // (re-)starts a 300ms quiet timer; each further qualifying event
+            // restarts the timer instead of being acted on immediately. Only
+            // once 300ms pass with no new event do we re-read the file, so a
+            // burst of writes (editors writing in place, or write+rename)
+            // settles before we load the final content.
+            let mut pending = false;
+            loop {
+                tokio::select! {
+                    maybe_res = rx.recv() => {
+                        let res = match maybe_res {
+                            Some(res) => res,
+                            None => break,
+                        };
+                        let event = match res {
+                            Ok(event) => event,
+                            Err(_) => continue,
+                        };
+                        // Compare by file name rather than the raw path string: for a
+                        // watch path with no directory component the parent resolves
+                        // to ".", and `notify` may report events against "./name" or a
+                        // canonicalized absolute path, neither of which compares equal
+                        // to `watch_path` itself.
+                        if matches!(event.kind, EventKind::Modify(_) | EventKind::Create(_))
+                            && event.paths.iter().any(|p| p.file_name() == watch_name.as_deref())
+                        {
+                            pending = true;
+                        }
+                    }
+                    _ = tokio::time::sleep(Duration::from_millis(300)), if pending => {
+                        pending = false;
+
+                        let iface_ref = match connection
+                            .object_server()
+                            .interface::<_, ResourceManager>(object_path.as_str())
+                            .await
+                        {
+                            Ok(iface_ref) => iface_ref,
+                            Err(e) => {
+                                logger.warn(&format!(
+                                    "reload of {watch_path} skipped - \
+                                     failed to look up {object_path} on the object server: {e}"
+                                ));
+                                continue;
+                            }
+                        };
+                        let mut manager = iface_ref.get_mut().await;
+                        manager.merge_from_file(&watch_path);
+                        if let Ok(ctxt) = SignalContext::new(&connection, object_path.as_str()) {
+                            manager.emit_resources_changed(&ctxt).await;
+                        }
+                    }
+                }
+            }
+        });
+
+        if let Some(old_handle) = self.watch_handles.insert(path, handle) {
+            old_handle.abort();
+        }
     }
 
     /// Getter for preprocessor
@@ -54,7 +343,7 @@ impl ResourceManager {
         Command::new(&self.preprocessor)
     }
 
-    /// Returns the content of the file after preprocessing
+    /// Returns the content of the file after preprocessing.
     fn get_preprocessed_file(&mut self, file_path: &str) -> Result<String, Box<dyn Error>> {
         if self.args.nocpp {
             self.logger.warn("wont use preprocessor - try running without --nocpp flag");
@@ -63,6 +352,14 @@ impl ResourceManager {
             self.logger.info(&config_str);
             return Ok(config_str);
         }
+
+        let file_bytes = fs::read(file_path)?;
+        let digest = preprocess_cache_key(&self.preprocessor, &file_bytes);
+        if let Some(cached) = self.preprocess_cache.get(&digest) {
+            self.logger.info(&format!("using cached preprocessed output for {file_path}"));
+            return Ok(cached.clone());
+        }
+
         let output_bytes = self.preprocessor()
             .arg(file_path)
             .output()?
@@ -72,6 +369,7 @@ impl ResourceManager {
         let conf_utf8 = String::from_utf8(output_bytes)?;
         self.logger.info("File preprocessed successfully...");
         self.logger.info(&conf_utf8);
+        self.preprocess_cache.insert(digest, conf_utf8.clone());
         Ok(conf_utf8)
     }
 
@@ -169,10 +467,26 @@ impl ResourceManager {
         self.emit_resources_changed(&ctxt).await;
     }
 
-    /// Returns all the matching 
+    /// DBus interface to start watching `path` for external changes,
+    /// reapplying it (overwriting any keys it defines) and notifying
+    /// clients whenever it changes on disk.
+    async fn watch(&mut self, path: String) {
+        self.start_watch(path);
+    }
+
+    /// DBus interface to stop watching `path` for changes.
+    async fn unwatch(&mut self, path: String) {
+        if let Some(handle) = self.watch_handles.remove(&path) {
+            handle.abort();
+            self.logger.info(&format!("Stopped watching {path}"));
+        }
+    }
+
+    /// Returns all the matching, resolved across `sources` (see
+    /// `add_source`) and `resources`
     /// *Note*: Also a DBus interface
     pub fn query(&self, q: &str) -> String {
-        let mut matches:Vec<_> = self.resources.iter()
+        let mut matches:Vec<_> = self.effective_resources().iter()
             .filter(|(k, _)| k.contains(q))
             .map(|(x, v)| format!("{} :\t{}", x, v))
             .collect();
@@ -184,12 +498,72 @@ impl ResourceManager {
         query_result
     }
 
-    /// Get the resource value
+    /// Get the resource value. A runtime-set value in `resources` always
+    /// wins; otherwise falls back to `sources` in priority order (lowest
+    /// priority number wins).
     pub fn get_resource(&self, key: &str) -> String {
-        self.resources
-            .get(key)
-            .unwrap_or(&String::from(""))
-            .to_owned()
+        if let Some(v) = self.resources.get(key) {
+            return v.to_owned();
+        }
+        for source in &self.sources {
+            if let Some(v) = source.values.get(key) {
+                return v.to_owned();
+            }
+        }
+        String::new()
+    }
+
+    /// Get the resource value, coerced to the type named by `conversion` (see
+    /// `Conversion`). On an unknown conversion name or a failed parse, the
+    /// raw string is returned instead and a warning is logged - clients that
+    /// don't care can always pull values with `get_resource`.
+    pub fn get_resource_typed(&self, key: &str, conversion: &str) -> Value<'static> {
+        let raw = self.get_resource(key);
+        let conversion = match Conversion::from_str(conversion) {
+            Ok(c) => c,
+            Err(e) => {
+                self.logger.warn(&format!("{e} - returning raw string"));
+                return Value::from(raw);
+            }
+        };
+        match conversion {
+            Conversion::Bytes => Value::from(raw),
+            Conversion::Integer => match raw.parse::<i64>() {
+                Ok(v) => Value::from(v),
+                Err(e) => {
+                    self.logger.warn(&format!("failed to parse '{raw}' as integer: {e}"));
+                    Value::from(raw)
+                }
+            },
+            Conversion::Float => match raw.parse::<f64>() {
+                Ok(v) => Value::from(v),
+                Err(e) => {
+                    self.logger.warn(&format!("failed to parse '{raw}' as float: {e}"));
+                    Value::from(raw)
+                }
+            },
+            Conversion::Boolean => match raw.parse::<bool>() {
+                Ok(v) => Value::from(v),
+                Err(e) => {
+                    self.logger.warn(&format!("failed to parse '{raw}' as boolean: {e}"));
+                    Value::from(raw)
+                }
+            },
+            Conversion::Timestamp => match DateTime::parse_from_rfc3339(&raw) {
+                Ok(dt) => Value::from(dt.timestamp()),
+                Err(e) => {
+                    self.logger.warn(&format!("failed to parse '{raw}' as an RFC3339 timestamp: {e}"));
+                    Value::from(raw)
+                }
+            },
+            Conversion::TimestampFmt(fmt) => match NaiveDateTime::parse_from_str(&raw, &fmt) {
+                Ok(dt) => Value::from(dt.and_utc().timestamp()),
+                Err(e) => {
+                    self.logger.warn(&format!("failed to parse '{raw}' with format '{fmt}': {e}"));
+                    Value::from(raw)
+                }
+            },
+        }
     }
 
     /// DBus interface to set the value of a resource. Overwrites exiting value.
@@ -234,9 +608,76 @@ impl ResourceManager {
         self.emit_resources_changed(&ctxt).await;
     }
 
-    /// DBus interface for getting resources values
+    /// DBus interface to persist the current resources map to `path`,
+    /// written atomically so a crash can't corrupt it. Returns whether the
+    /// dump succeeded.
+    pub fn dump(&self, path: &str) -> bool {
+        match self.dump_to_file(path) {
+            Ok(()) => true,
+            Err(e) => {
+                self.logger.error(&format!("failed to dump resources to {path}: {e}"));
+                false
+            }
+        }
+    }
+
+    /// DBus interface to merge a resources map previously written by `dump`
+    /// back in from `path`, overwriting any keys in common, then notify
+    /// clients. Returns whether the restore succeeded.
+    pub async fn restore(
+        &mut self,
+        #[zbus(signal_context)]
+        ctxt: SignalContext<'_>,
+        path: &str
+    ) -> bool {
+        match self.restore_from_file(path) {
+            Ok(()) => {
+                self.emit_resources_changed(&ctxt).await;
+                true
+            }
+            Err(e) => {
+                self.logger.error(&format!("failed to restore resources from {path}: {e}"));
+                false
+            }
+        }
+    }
+
+    /// DBus interface to add a layered resource source at `path` with the
+    /// given `priority` (lower wins over higher when resolving a key),
+    /// reading and parsing the file immediately and notifying clients.
+    pub async fn add_source(
+        &mut self,
+        #[zbus(signal_context)]
+        ctxt: SignalContext<'_>,
+        path: String,
+        priority: i32,
+    ) -> bool {
+        match self.load_source(&path, priority) {
+            Ok(()) => {
+                self.emit_resources_changed(&ctxt).await;
+                true
+            }
+            Err(e) => {
+                self.logger.error(&format!("failed to add source {path}: {e}"));
+                false
+            }
+        }
+    }
+
+    /// DBus interface listing configured sources as `path (priority N)`
+    /// strings, in priority order, so a client can inspect which file a
+    /// value would resolve from.
+    pub fn list_sources(&self) -> Vec<String> {
+        self.sources
+            .iter()
+            .map(|s| format!("{} (priority {})", s.path, s.priority))
+            .collect()
+    }
+
+    /// DBus interface for getting resources values, resolved across
+    /// `sources` and `resources`
     #[dbus_interface(property)]
     pub fn resources(&self) -> HashMap<String, String> {
-        self.resources.clone()
+        self.effective_resources()
     }
 }
\ No newline at end of file
@@ -0,0 +1,125 @@
+#![cfg(test)]
+
+use super::*;
+use std::fs;
+
+#[test]
+fn conversion_from_str_parses_known_names() {
+    assert_eq!(Conversion::from_str("int").unwrap(), Conversion::Integer);
+    assert_eq!(Conversion::from_str("integer").unwrap(), Conversion::Integer);
+    assert_eq!(Conversion::from_str("float").unwrap(), Conversion::Float);
+    assert_eq!(Conversion::from_str("bool").unwrap(), Conversion::Boolean);
+    assert_eq!(Conversion::from_str("boolean").unwrap(), Conversion::Boolean);
+    assert_eq!(Conversion::from_str("string").unwrap(), Conversion::Bytes);
+    assert_eq!(Conversion::from_str("bytes").unwrap(), Conversion::Bytes);
+    assert_eq!(Conversion::from_str("timestamp").unwrap(), Conversion::Timestamp);
+}
+
+#[test]
+fn conversion_from_str_parses_timestamp_with_format() {
+    assert_eq!(
+        Conversion::from_str("timestamp|%Y-%m-%d").unwrap(),
+        Conversion::TimestampFmt("%Y-%m-%d".to_string())
+    );
+}
+
+#[test]
+fn conversion_from_str_rejects_unknown() {
+    assert!(Conversion::from_str("timestampx").is_err());
+    assert!(Conversion::from_str("enum").is_err());
+    assert!(Conversion::from_str("").is_err());
+}
+
+#[test]
+fn preprocess_cache_key_is_stable_for_identical_input() {
+    let a = preprocess_cache_key("/usr/bin/cpp", b"foo: bar");
+    let b = preprocess_cache_key("/usr/bin/cpp", b"foo: bar");
+    assert_eq!(a, b);
+}
+
+#[test]
+fn preprocess_cache_key_changes_with_file_contents() {
+    let a = preprocess_cache_key("/usr/bin/cpp", b"foo: bar");
+    let b = preprocess_cache_key("/usr/bin/cpp", b"foo: baz");
+    assert_ne!(a, b);
+}
+
+#[test]
+fn preprocess_cache_key_changes_with_preprocessor() {
+    let a = preprocess_cache_key("/usr/bin/cpp", b"foo: bar");
+    let b = preprocess_cache_key("/usr/bin/cpp-12", b"foo: bar");
+    assert_ne!(a, b);
+}
+
+/// Unique-per-test path under the system temp dir, so parallel test runs
+/// don't collide on the same file.
+fn temp_path(name: &str) -> String {
+    std::env::temp_dir()
+        .join(format!("resmand-test-{}-{}", std::process::id(), name))
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn resources_round_trip_through_dump_and_restore() {
+    let path = temp_path("round-trip");
+    let mut resources = HashMap::new();
+    resources.insert("foo".to_string(), "bar".to_string());
+    resources.insert("baz".to_string(), "qux".to_string());
+
+    write_resources_atomic(&resources, &path).expect("write should succeed");
+    let restored = read_resources(&path).expect("read should succeed");
+    assert_eq!(restored, resources);
+
+    fs::remove_file(&path).ok();
+}
+
+#[test]
+fn writing_atomically_twice_keeps_previous_version_as_bak() {
+    let path = temp_path("bak");
+    let mut first = HashMap::new();
+    first.insert("foo".to_string(), "1".to_string());
+    write_resources_atomic(&first, &path).expect("first write should succeed");
+
+    let mut second = HashMap::new();
+    second.insert("foo".to_string(), "2".to_string());
+    write_resources_atomic(&second, &path).expect("second write should succeed");
+
+    let current = read_resources(&path).expect("current read should succeed");
+    assert_eq!(current, second);
+
+    let backup = read_resources(&format!("{path}.bak")).expect("backup read should succeed");
+    assert_eq!(backup, first);
+
+    fs::remove_file(&path).ok();
+    fs::remove_file(format!("{path}.bak")).ok();
+}
+
+#[test]
+fn resolve_effective_prefers_lower_priority_number_among_sources() {
+    let mut high = HashMap::new();
+    high.insert("foo".to_string(), "from-high-priority".to_string());
+    let mut low = HashMap::new();
+    low.insert("foo".to_string(), "from-low-priority".to_string());
+
+    let sources = vec![
+        Source { path: "high.cfg".to_string(), priority: 0, values: high },
+        Source { path: "low.cfg".to_string(), priority: 10, values: low },
+    ];
+
+    let effective = resolve_effective(&HashMap::new(), &sources);
+    assert_eq!(effective.get("foo"), Some(&"from-high-priority".to_string()));
+}
+
+#[test]
+fn resolve_effective_prefers_runtime_resources_over_any_source() {
+    let mut source_values = HashMap::new();
+    source_values.insert("foo".to_string(), "from-source".to_string());
+    let sources = vec![Source { path: "a.cfg".to_string(), priority: 0, values: source_values }];
+
+    let mut resources = HashMap::new();
+    resources.insert("foo".to_string(), "from-runtime".to_string());
+
+    let effective = resolve_effective(&resources, &sources);
+    assert_eq!(effective.get("foo"), Some(&"from-runtime".to_string()));
+}